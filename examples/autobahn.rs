@@ -0,0 +1,71 @@
+//! Drives the client in this crate against the Autobahn TestSuite fuzzing
+//! server (https://github.com/crossbario/autobahn-testsuite), for use as a
+//! manual conformance check against RFC 6455 — running it is not itself a
+//! claim that every case passes. Every endpoint on the fuzzing server is
+//! itself a WebSocket connection, so this is entirely exercised through
+//! `ws::client::Client`:
+//!
+//!   cargo run --example autobahn
+//!
+//! assumes a fuzzing server is listening on `AUTOBAHN_HOST`
+//! (default `127.0.0.1:9001`). Inspect the generated report under
+//! `reports/clients/index.html` to see which cases actually passed.
+
+use std::error::Error;
+
+use ws::client::{Client, Message};
+
+const AGENT: &str = "ws";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let host = std::env::var("AUTOBAHN_HOST").unwrap_or_else(|_| "127.0.0.1:9001".into());
+
+    let case_count = get_case_count(&host).await?;
+    println!("running {} cases against {} as agent {}", case_count, host, AGENT);
+
+    for case in 1..=case_count {
+        if let Err(e) = run_case(&host, case).await {
+            eprintln!("case {}: {}", case, e);
+        }
+    }
+
+    update_reports(&host).await?;
+
+    Ok(())
+}
+
+async fn get_case_count(host: &str) -> Result<usize, Box<dyn Error>> {
+    let mut client = Client::connect(&format!("ws://{}/getCaseCount", host)).await?;
+
+    match client.receive().await? {
+        Message::Data(_, payload) => Ok(std::str::from_utf8(&payload)?.trim().parse()?),
+        Message::Closed => Err("connection closed before the case count was sent".into()),
+    }
+}
+
+/// Echoes every message the server sends back verbatim until the server
+/// closes the case. `Client::receive` reassembles fragmented messages
+/// before returning them, so a message echoed here is re-sent as a single
+/// unfragmented frame even if the server sent it in pieces; only the
+/// opcode is preserved.
+async fn run_case(host: &str, case: usize) -> Result<(), Box<dyn Error>> {
+    let uri = format!("ws://{}/runCase?case={}&agent={}", host, case, AGENT);
+    let mut client = Client::connect(&uri).await?;
+
+    loop {
+        match client.receive().await? {
+            Message::Data(opcode, payload) => client.send(opcode, &payload).await?,
+            Message::Closed => return Ok(()),
+        }
+    }
+}
+
+async fn update_reports(host: &str) -> Result<(), Box<dyn Error>> {
+    let uri = format!("ws://{}/updateReports?agent={}", host, AGENT);
+    let mut client = Client::connect(&uri).await?;
+
+    while let Message::Data(_, _) = client.receive().await? {}
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use base64::encode;
+use bytes::{Bytes, BytesMut};
+use hyper::{client::conn::Builder, upgrade::Upgraded, Body, Request, StatusCode, Uri};
+use rand::RngCore;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::websocket::{Frame, FragmentedMessage, Opcode, Role};
+use crate::{decode, generate_accept_key, read_frame, Error};
+
+/// A message received from the server: either a complete (possibly
+/// reassembled) data message, or notice that the server closed the
+/// connection.
+pub enum Message {
+    Data(Opcode, Bytes),
+    Closed,
+}
+
+fn random_mask() -> [u8; 4] {
+    let mut mask = [0u8; 4];
+    rand::thread_rng().fill_bytes(&mut mask);
+    mask
+}
+
+/// A client-side WebSocket connection, obtained via [`Client::connect`].
+pub struct Client {
+    conn: Upgraded,
+    buffer: BytesMut,
+}
+
+impl Client {
+    /// Performs the HTTP/1.1 Upgrade handshake against `uri`: sends a
+    /// random `Sec-WebSocket-Key` nonce and checks that the server's
+    /// `Sec-WebSocket-Accept` is the base64-encoded SHA-1 of that key
+    /// concatenated with `WS_GUID`, the same value `upgrade` computes on
+    /// the server side.
+    pub async fn connect(uri: &str) -> std::result::Result<Self, Error> {
+        let uri: Uri = uri
+            .parse()
+            .map_err(|_| Error::ParseError(format!("invalid URI: {}", uri)))?;
+        let host = uri
+            .host()
+            .ok_or_else(|| Error::ParseError(format!("URI has no host: {}", uri)))?;
+        let port = uri.port_u16().unwrap_or(80);
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+        let stream = TcpStream::connect((host, port)).await?;
+        let (mut sender, connection) = Builder::new().handshake(stream).await?;
+
+        tokio::task::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("client connection error: {}", e);
+            }
+        });
+
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let websocket_key = encode(nonce);
+
+        let request = Request::builder()
+            .method("GET")
+            .uri(path)
+            .header("Host", host)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Key", &websocket_key)
+            .header("Sec-WebSocket-Version", "13")
+            .body(Body::empty())?;
+
+        let response = sender.send_request(request).await?;
+
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(Error::ParseError(format!(
+                "server refused the upgrade: {}",
+                response.status()
+            )));
+        }
+
+        let accept = response
+            .headers()
+            .get("Sec-WebSocket-Accept")
+            .ok_or_else(|| Error::ParseError("missing Sec-WebSocket-Accept".into()))?;
+
+        if accept.as_bytes() != generate_accept_key(websocket_key.as_bytes()).as_bytes() {
+            return Err(Error::ParseError(
+                "Sec-WebSocket-Accept does not match the sent key".into(),
+            ));
+        }
+
+        let conn = hyper::upgrade::on(response).await?;
+
+        Ok(Self {
+            conn,
+            buffer: BytesMut::with_capacity(4096),
+        })
+    }
+
+    /// Sends a single, unfragmented frame, masked with a fresh random key
+    /// as the spec requires for every client->server frame.
+    pub async fn send(&mut self, opcode: Opcode, payload: &[u8]) -> std::result::Result<(), Error> {
+        let frame = Frame::to_bytes_masked(true, opcode, random_mask(), payload);
+        self.conn.write_all(&frame).await?;
+        Ok(())
+    }
+
+    /// Reads frames until a complete message is assembled, automatically
+    /// replying to pings with a pong and to a close frame with a close
+    /// frame of its own.
+    pub async fn receive(&mut self) -> std::result::Result<Message, Error> {
+        let mut assembling: Option<FragmentedMessage> = None;
+
+        loop {
+            let (frame, raw_payload) =
+                match read_frame(&mut self.conn, &mut self.buffer, Role::Client).await? {
+                    Some(frame) => frame,
+                    None => return Ok(Message::Closed),
+                };
+
+            let mut payload = vec![0; frame.length as usize];
+            let mask = frame.masking_key.to_be_bytes();
+            decode(&mut payload, &raw_payload, mask, frame.length as usize);
+
+            match frame.opcode {
+                Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                    let state = assembling.get_or_insert_with(FragmentedMessage::default);
+                    state.push(frame.opcode, &payload)?;
+
+                    if frame.fin == 1 {
+                        let (opcode, payload) = assembling.take().unwrap().finish()?;
+                        return Ok(Message::Data(opcode, payload));
+                    }
+                }
+                Opcode::Ping => {
+                    self.send(Opcode::Pong, &payload).await?;
+                }
+                Opcode::Pong => {}
+                Opcode::Close => {
+                    self.send(Opcode::Close, &payload).await?;
+                    return Ok(Message::Closed);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+pub mod client;
+pub mod websocket;
+
+use base64::encode;
+use bytes::{Bytes, BytesMut};
+use crypto::{digest::Digest, sha1::Sha1};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use websocket::{Frame, ProtocolError, Role};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Unknown error.")]
+    Any,
+    #[error("I/O error: {0}.")]
+    IoError(#[from] std::io::Error),
+    #[error("Parser error: {0}.")]
+    ParseError(String),
+    #[error("Protocol error: {0}.")]
+    Protocol(#[from] ProtocolError),
+    #[error("Upgrade error: {0}.")]
+    Upgrade(#[from] UpgradeError),
+    #[error("hyper error: {0}.")]
+    Hyper(#[from] hyper::Error),
+    #[error("hyper::http error: {0}.")]
+    HyperHttp(#[from] hyper::http::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum UpgradeError {
+    #[error("Invalid Sec-WebSocket-Version.")]
+    InvalidVersion,
+    #[error("Required header not found: {0}.")]
+    HeaderNotFound(String),
+}
+
+pub const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// XORs `len` bytes of `source` with the rotating 4-byte `mask`, writing the
+/// result into `target`. Processes the unaligned head and tail byte-at-a-
+/// time, and XORs a full `usize` word per iteration over the aligned
+/// middle, which is significantly faster than a naive byte-at-a-time loop
+/// for large payloads.
+pub fn decode(target: &mut [u8], source: &[u8], mask: [u8; 4], len: usize) {
+    const WORD: usize = std::mem::size_of::<usize>();
+
+    let misalignment = target.as_ptr() as usize % WORD;
+    let head_len = if misalignment == 0 { 0 } else { WORD - misalignment };
+    let head_len = head_len.min(len);
+
+    for i in 0..head_len {
+        target[i] = source[i] ^ mask[i % 4];
+    }
+
+    // The key must be rotated by how many bytes the head consumed so that
+    // the wide mask lines up with the stream position of the aligned
+    // middle.
+    let mut key = [0u8; WORD];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = mask[(head_len + i) % 4];
+    }
+    let word_mask = usize::from_ne_bytes(key);
+
+    let aligned_len = (len - head_len) / WORD * WORD;
+    let mut i = head_len;
+    while i < head_len + aligned_len {
+        // SAFETY: `head_len` was chosen so `target` is `WORD`-aligned from
+        // this offset on, and at least `WORD` bytes remain in both slices,
+        // so the aligned write to `target` and the (possibly unaligned)
+        // read from `source` are both in-bounds and valid.
+        unsafe {
+            let source_word = (source.as_ptr().add(i) as *const usize).read_unaligned();
+            (target.as_mut_ptr().add(i) as *mut usize).write(source_word ^ word_mask);
+        }
+        i += WORD;
+    }
+
+    for i in (head_len + aligned_len)..len {
+        target[i] = source[i] ^ mask[i % 4];
+    }
+}
+
+/// Number of leading bytes needed to know how long the rest of the header
+/// is (the FIN/opcode byte and the mask-bit/length-hint byte).
+const MIN_HEADER_LEN: usize = 2;
+
+/// Returns the total header length (everything up to but not including the
+/// payload), once `buf` holds enough bytes to compute it from the mask bit
+/// and length hint in the second header byte. `None` means more bytes are
+/// needed before the header length is even knowable.
+fn header_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < MIN_HEADER_LEN {
+        return None;
+    }
+
+    let mask_bit = buf[1] & 0x80 != 0;
+    let extended_len = match buf[1] & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+
+    Some(MIN_HEADER_LEN + extended_len + if mask_bit { 4 } else { 0 })
+}
+
+/// Reads a single complete WebSocket frame from `conn`, buffering across as
+/// many `read_buf` calls as it takes for the header and payload to fully
+/// arrive, and retaining any bytes read past the end of this frame in `buf`
+/// for the next call. Returns `Ok(None)` on a clean EOF with no partial
+/// frame pending.
+///
+/// `buf` must be reused across calls for the same connection so that bytes
+/// belonging to the next frame (e.g. when two frames land in a single
+/// `read_buf`) are not lost.
+pub async fn read_frame<C>(
+    conn: &mut C,
+    buf: &mut BytesMut,
+    role: Role,
+) -> std::result::Result<Option<(Frame, Bytes)>, Error>
+where
+    C: AsyncRead + Unpin,
+{
+    let mut header: Option<Frame> = None;
+
+    loop {
+        if header.is_none() {
+            if let Some(len) = header_len(buf) {
+                if buf.len() >= len {
+                    let (_, frame) = Frame::from_bytes(buf[..len].to_vec(), role)?;
+                    header = Some(frame);
+                }
+            }
+        }
+
+        if let Some(frame) = &header {
+            let len = header_len(buf).expect("header already parsed above");
+            let total_len = len + frame.length as usize;
+
+            if buf.len() >= total_len {
+                let mut frame_bytes = buf.split_to(total_len);
+                let payload = frame_bytes.split_off(len).freeze();
+                return Ok(Some((header.take().unwrap(), payload)));
+            }
+        }
+
+        buf.reserve(4096);
+        if conn.read_buf(buf).await? == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+}
+
+fn concat<T: Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    a.iter().cloned().chain(b.iter().cloned()).collect()
+}
+
+pub fn generate_accept_key(websocket_key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    let combined = concat(websocket_key, WS_GUID.as_bytes());
+    hasher.input(&combined);
+    let mut output_buf = vec![0; hasher.output_bytes()];
+    hasher.result(&mut output_buf);
+    let accept_key = encode(output_buf);
+    accept_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode;
+
+    fn naive_decode(source: &[u8], mask: [u8; 4]) -> Vec<u8> {
+        source
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect()
+    }
+
+    #[test]
+    fn decode_matches_naive_byte_wise_xor() {
+        let masks = [[0xDE, 0xAD, 0xBE, 0xEF], [0x00, 0x00, 0x00, 0x00], [0xFF, 0x00, 0xFF, 0x00]];
+
+        // Cover both sides of every alignment boundary the word-wise fast
+        // path could get wrong: empty, shorter than a word, exactly a word,
+        // and several lengths past it.
+        let lens = [0, 1, 2, 3, 7, 8, 9, 15, 16, 17, 64, 257];
+
+        for mask in masks {
+            for len in lens {
+                let source: Vec<u8> = (0..len).map(|i| (i * 31 + 7) as u8).collect();
+                let expected = naive_decode(&source, mask);
+
+                // Write into a byte-offset sub-slice of a larger buffer for
+                // each offset so the fast path's head/tail splitting runs
+                // against every possible misalignment, not just whatever a
+                // fresh `Vec<u8>` happens to allocate at.
+                for offset in 0..8 {
+                    let mut backing = vec![0u8; offset + len];
+                    decode(&mut backing[offset..], &source, mask, len);
+                    assert_eq!(
+                        backing[offset..],
+                        expected[..],
+                        "len = {}, offset = {}, mask = {:?}",
+                        len,
+                        offset,
+                        mask
+                    );
+                }
+            }
+        }
+    }
+}
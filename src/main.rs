@@ -1,71 +1,77 @@
-mod websocket;
-
-use base64::encode;
 use bytes::BytesMut;
 use color_eyre::Report;
-use crypto::{digest::Digest, sha1::Sha1};
 use hyper::{
-    header::HeaderName,
     service::{make_service_fn, service_fn},
     upgrade::Upgraded,
     Body, Request, Response, Server, StatusCode,
 };
-use nom::AsBytes;
-use std::{convert::Infallible, fmt::Display, future::Future, net::SocketAddr};
-use thiserror::Error;
-use tokio::io::AsyncReadExt;
+use std::{
+    convert::{Infallible, TryFrom},
+    future::Future,
+    net::SocketAddr,
+};
+use tokio::io::AsyncWriteExt;
 use tokio::task;
-use websocket::Frame;
+use ws::websocket::{CloseCode, Frame, FragmentedMessage, Opcode, Role};
+use ws::{decode, generate_accept_key, read_frame, Error, UpgradeError};
 
 type DefaultResult<T> = std::result::Result<T, Report>;
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Error, Debug)]
-pub enum Error {
-    #[error("Unknown error.")]
-    Any,
-    #[error("I/O error: {0}.")]
-    IoError(#[from] std::io::Error),
-    #[error("Parser error: {0}.")]
-    ParseError(String),
-    #[error("Upgrade error: {0}.")]
-    Upgrade(#[from] UpgradeError),
-    #[error("hyper error: {0}.")]
-    Hyper(#[from] hyper::Error),
-    #[error("hyper::http error: {0}.")]
-    HyperHttp(#[from] hyper::http::Error),
-}
-
-#[derive(Error, Debug)]
-pub enum UpgradeError {
-    #[error("Invalid Sec-WebSocket-Version.")]
-    InvalidVersion,
-    #[error("Required header not found: {0}.")]
-    HeaderNotFound(String),
-}
-
-const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+async fn handle_upgraded(mut conn: Upgraded) -> std::result::Result<(), Error> {
+    let result = handle_messages(&mut conn).await;
 
-fn decode(target: &mut [u8], source: &[u8], mask: [u8; 4], len: usize) {
-    for i in 0..len {
-        target[i] = source[i] ^ mask[i % 4];
+    if let Err(Error::Protocol(ref protocol_error)) = result {
+        let close_code = protocol_error.close_code() as u16;
+        let reply = Frame::to_bytes(true, Opcode::Close, &close_code.to_be_bytes());
+        conn.write_all(&reply).await?;
     }
+
+    result
 }
 
-async fn handle_upgraded(mut conn: Upgraded) -> std::result::Result<(), Error> {
+async fn handle_messages(conn: &mut Upgraded) -> std::result::Result<(), Error> {
+    let mut assembling: Option<FragmentedMessage> = None;
     let mut buffer = BytesMut::with_capacity(4096);
-    let len = conn.read_buf(&mut buffer).await?;
-    let frame_bytes = (&buffer[..len]).to_owned();
-    let (rest, frame) = Frame::from_bytes(frame_bytes)?;
-
-    let mut message = vec![0; frame.length as usize];
-    let mask = frame.masking_key.to_be_bytes();
 
-    decode(&mut message, &rest, mask, frame.length as usize);
-
-    println!("message: {}", String::from_utf8_lossy(&message));
-
-    Ok(())
+    loop {
+        let (frame, payload) = match read_frame(conn, &mut buffer, Role::Server).await? {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let mut message = vec![0; frame.length as usize];
+        let mask = frame.masking_key.to_be_bytes();
+
+        decode(&mut message, &payload, mask, frame.length as usize);
+
+        match frame.opcode {
+            Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                let state = assembling.get_or_insert_with(FragmentedMessage::default);
+                state.push(frame.opcode, &message)?;
+
+                if frame.fin == 1 {
+                    let (opcode, payload) = assembling.take().unwrap().finish()?;
+                    let reply = Frame::to_bytes(true, opcode, &payload);
+                    conn.write_all(&reply).await?;
+                }
+            }
+            Opcode::Ping => {
+                let reply = Frame::to_bytes(true, Opcode::Pong, &message);
+                conn.write_all(&reply).await?;
+            }
+            Opcode::Pong => {}
+            Opcode::Close => {
+                if message.len() >= 2 {
+                    CloseCode::try_from(u16::from_be_bytes([message[0], message[1]]))?;
+                }
+
+                let reply = Frame::to_bytes(true, Opcode::Close, &message);
+                conn.write_all(&reply).await?;
+                return Ok(());
+            }
+        }
+    }
 }
 
 fn spawn_and_log_error<F>(fut: F) -> task::JoinHandle<()>
@@ -118,20 +124,6 @@ async fn upgrade(req: Request<Body>) -> Result<Response<Body>> {
     Ok(response)
 }
 
-fn concat<T: Clone>(a: &[T], b: &[T]) -> Vec<T> {
-    a.iter().cloned().chain(b.iter().cloned()).collect()
-}
-
-fn generate_accept_key(websocket_key: &[u8]) -> String {
-    let mut hasher = Sha1::new();
-    let combined = concat(websocket_key, WS_GUID.as_bytes());
-    hasher.input(&combined);
-    let mut output_buf = vec![0; hasher.output_bytes()];
-    hasher.result(&mut output_buf);
-    let accept_key = encode(output_buf);
-    accept_key
-}
-
 fn setup() -> DefaultResult<()> {
     if std::env::var("RUST_LIB_BACKTRACE").is_err() {
         std::env::set_var("RUST_LIB_BACKTRACE", "1")
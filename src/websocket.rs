@@ -1,13 +1,14 @@
-use std::convert::Infallible;
+use std::convert::{Infallible, TryFrom};
 
 use bytes::Bytes;
-use nom::combinator::{cond, map_res};
 use nom::error::{ContextError, Error as NomError, ErrorKind as NomErrorKind};
 use nom::{
-    bits::bits, bits::complete::take as take_bits, combinator::map,
-    error::ParseError as NomParseError, number::complete::be_u32, sequence::tuple,
+    bits::bits,
+    bits::complete::take as take_bits,
+    error::ParseError as NomParseError,
+    number::complete::{be_u16, be_u32, be_u64},
+    sequence::tuple,
 };
-use pretty_hex::*;
 
 #[derive(Debug)]
 pub enum ErrorKind {
@@ -42,13 +43,167 @@ impl<I> ContextError<I> for Error<I> {
 pub type Input<'a> = &'a [u8];
 pub type Result<'a, T> = nom::IResult<Input<'a>, T, Error<Input<'a>>>;
 
+/// A WebSocket protocol violation, distinguished so the caller can choose
+/// the right close code (RFC 6455 section 7.4.1) to send back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    ReservedBitsSet,
+    UnknownOpcode(u8),
+    FragmentedControlFrame,
+    ControlPayloadTooLong,
+    InvalidExtendedLength(u64),
+    PayloadTooLarge(u64),
+    MaskBitMismatch,
+    InvalidCloseCode(u16),
+    InvalidUtf8,
+    UnexpectedContinuation,
+    Other(String),
+}
+
+impl ProtocolError {
+    /// The close code a server should send back when tearing down the
+    /// connection after this error.
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            ProtocolError::InvalidUtf8 => CloseCode::InvalidPayload,
+            ProtocolError::PayloadTooLarge(_) => CloseCode::TooLarge,
+            _ => CloseCode::ProtocolError,
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::ReservedBitsSet => write!(f, "reserved RSV bits are set"),
+            ProtocolError::UnknownOpcode(opcode) => write!(f, "unknown opcode: {:#x}", opcode),
+            ProtocolError::FragmentedControlFrame => {
+                write!(f, "control frames must not be fragmented")
+            }
+            ProtocolError::ControlPayloadTooLong => {
+                write!(f, "control frame payload exceeds 125 bytes")
+            }
+            ProtocolError::InvalidExtendedLength(len) => {
+                write!(f, "extended payload length has its MSB set: {:#x}", len)
+            }
+            ProtocolError::PayloadTooLarge(len) => {
+                write!(
+                    f,
+                    "payload length {} exceeds the maximum of {} bytes",
+                    len, MAX_FRAME_PAYLOAD_LEN
+                )
+            }
+            ProtocolError::MaskBitMismatch => {
+                write!(f, "frame's mask bit does not match the expected direction")
+            }
+            ProtocolError::InvalidCloseCode(code) => write!(f, "invalid close code: {}", code),
+            ProtocolError::InvalidUtf8 => write!(f, "invalid UTF-8 in text message"),
+            ProtocolError::UnexpectedContinuation => write!(
+                f,
+                "continuation frame without a preceding unfinished message"
+            ),
+            ProtocolError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl<I: std::fmt::Debug> From<nom::Err<Error<I>>> for ProtocolError {
+    fn from(err: nom::Err<Error<I>>) -> Self {
+        let context = match err {
+            nom::Err::Incomplete(needed) => format!("incomplete frame: {:?}", needed),
+            nom::Err::Error(e) | nom::Err::Failure(e) => format!("{:?}", e.errors),
+        };
+        ProtocolError::Other(context)
+    }
+}
+
+/// The WebSocket frame opcode (RFC 6455 section 5.2). Reserved opcodes are
+/// rejected rather than represented, so a `Frame` can only ever hold one of
+/// these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xA,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0x0 => Ok(Opcode::Continuation),
+            0x1 => Ok(Opcode::Text),
+            0x2 => Ok(Opcode::Binary),
+            0x8 => Ok(Opcode::Close),
+            0x9 => Ok(Opcode::Ping),
+            0xA => Ok(Opcode::Pong),
+            other => Err(ProtocolError::UnknownOpcode(other)),
+        }
+    }
+}
+
+/// The WebSocket close status code (RFC 6455 section 7.4.1), sent as the
+/// first two bytes of a close frame's payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    Normal = 1000,
+    GoingAway = 1001,
+    ProtocolError = 1002,
+    Unsupported = 1003,
+    InvalidPayload = 1007,
+    PolicyViolation = 1008,
+    TooLarge = 1009,
+    MissingExtension = 1010,
+    InternalError = 1011,
+}
+
+impl TryFrom<u16> for CloseCode {
+    type Error = ProtocolError;
+
+    fn try_from(value: u16) -> std::result::Result<Self, Self::Error> {
+        match value {
+            1000 => Ok(CloseCode::Normal),
+            1001 => Ok(CloseCode::GoingAway),
+            1002 => Ok(CloseCode::ProtocolError),
+            1003 => Ok(CloseCode::Unsupported),
+            1007 => Ok(CloseCode::InvalidPayload),
+            1008 => Ok(CloseCode::PolicyViolation),
+            1009 => Ok(CloseCode::TooLarge),
+            1010 => Ok(CloseCode::MissingExtension),
+            1011 => Ok(CloseCode::InternalError),
+            other => Err(ProtocolError::InvalidCloseCode(other)),
+        }
+    }
+}
+
+/// The largest payload a single frame may declare. Without a cap, a single
+/// crafted length header would make a connection allocate an arbitrarily
+/// large buffer before a single payload byte has even arrived.
+pub const MAX_FRAME_PAYLOAD_LEN: u64 = 16 * 1024 * 1024;
+
+/// Which side of the connection a frame is being parsed on. RFC 6455
+/// section 5.1 requires a server to reject any frame that isn't masked and
+/// a client to reject any frame that is, so `Frame::from_bytes` needs to
+/// know which direction it's validating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
 #[derive(Debug)]
 pub struct Frame {
     pub fin: u8,
     pub rsv: u8,
     pub mask: u8,
-    pub opcode: u8,
-    pub length: u8,
+    pub opcode: Opcode,
+    pub length: u64,
     pub masking_key: u32,
 }
 
@@ -66,9 +221,9 @@ impl<I> nom::ErrorConvert<Error<I>> for NomError<(I, usize)> {
     }
 }
 
-impl<I> From<nom::Err<Error<I>>> for crate::Error {
-    fn from(_: nom::Err<Error<I>>) -> Self {
-        crate::Error::Derp
+impl<I: std::fmt::Debug> From<nom::Err<Error<I>>> for crate::Error {
+    fn from(err: nom::Err<Error<I>>) -> Self {
+        crate::Error::Protocol(ProtocolError::from(err))
     }
 }
 
@@ -87,32 +242,181 @@ impl Frame {
         )))(input)
     }
 
-    pub fn from_bytes(input: Vec<u8>) -> std::result::Result<(Vec<u8>, Frame), crate::Error> {
-        println!("input: {}", input.hex_dump());
+    /// Writes the FIN/opcode byte and the length header (choosing the
+    /// 16-bit or 64-bit extended form as needed) into `out`, setting the
+    /// mask bit and appending the masking key when `mask` is `Some`.
+    fn encode_header(fin: bool, opcode: Opcode, mask: Option<[u8; 4]>, len: usize, out: &mut Vec<u8>) {
+        let fin_bit = if fin { 0x80 } else { 0x00 };
+        out.push(fin_bit | (opcode as u8 & 0x0F));
+
+        let mask_bit = if mask.is_some() { 0x80 } else { 0x00 };
+        if len <= 125 {
+            out.push(mask_bit | len as u8);
+        } else if len <= u16::MAX as usize {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        if let Some(mask) = mask {
+            out.extend_from_slice(&mask);
+        }
+    }
+
+    /// Serializes a single unmasked frame with the given opcode and
+    /// payload, choosing the 16-bit or 64-bit extended-length header as
+    /// needed. Server frames are never masked, so the mask bit is always
+    /// left unset.
+    pub fn to_bytes(fin: bool, opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(payload.len() + 10);
+        Self::encode_header(fin, opcode, None, payload.len(), &mut out);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// Serializes a single frame masked with `mask`, as required for every
+    /// frame a client sends to a server.
+    pub fn to_bytes_masked(fin: bool, opcode: Opcode, mask: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(payload.len() + 14);
+        Self::encode_header(fin, opcode, Some(mask), payload.len(), &mut out);
+
+        let mut masked = vec![0; payload.len()];
+        crate::decode(&mut masked, payload, mask, payload.len());
+        out.extend_from_slice(&masked);
+
+        out
+    }
+
+    pub fn from_bytes(
+        input: Vec<u8>,
+        role: Role,
+    ) -> std::result::Result<(Vec<u8>, Frame), crate::Error> {
         let (rest, parsed) = Self::parse_pre_payload(&input)?;
         let (fin, rsv, opcode, mask, payload_hint) = parsed;
-        let payload_word_len = match payload_hint {
-            126 => 16,
-            127 => 64,
-            _ => payload_hint,
+
+        if rsv != 0 {
+            return Err(ProtocolError::ReservedBitsSet.into());
+        }
+
+        let mask_is_valid = match role {
+            Role::Server => mask == 1,
+            Role::Client => mask == 0,
+        };
+        if !mask_is_valid {
+            return Err(ProtocolError::MaskBitMismatch.into());
+        }
+
+        let opcode = Opcode::try_from(opcode)?;
+
+        // 126 and 127 are not literal lengths but markers for a 16-bit or
+        // 64-bit extended length field following the first two bytes.
+        let (rest, length) = match payload_hint {
+            126 => {
+                let (rest, len) = be_u16(rest)?;
+                (rest, len as u64)
+            }
+            127 => {
+                let (rest, len) = be_u64(rest)?;
+                // RFC 6455 section 5.2: the most significant bit of the
+                // 64-bit extended length must be 0.
+                if len & (1 << 63) != 0 {
+                    return Err(ProtocolError::InvalidExtendedLength(len).into());
+                }
+                (rest, len)
+            }
+            _ => (rest, payload_hint as u64),
+        };
+
+        if length > MAX_FRAME_PAYLOAD_LEN {
+            return Err(ProtocolError::PayloadTooLarge(length).into());
+        }
+
+        let is_control = matches!(opcode, Opcode::Close | Opcode::Ping | Opcode::Pong);
+        if is_control && fin != 1 {
+            return Err(ProtocolError::FragmentedControlFrame.into());
+        }
+        if is_control && length > 125 {
+            return Err(ProtocolError::ControlPayloadTooLong.into());
+        }
+
+        // Only frames sent client->server are masked; a server reply has
+        // no masking key at all, so skip the field rather than misreading
+        // payload bytes as a key. `0` is harmless even when used by the
+        // caller, since XORing with an all-zero key is a no-op.
+        let (rest, masking_key) = if mask == 1 {
+            Self::parse_masking_key(rest)?
+        } else {
+            (rest, 0)
+        };
+
+        let frame = Self {
+            fin,
+            rsv,
+            mask,
+            opcode,
+            length,
+            masking_key,
         };
-        let payload = cond(payload_word_len >= 16, take_bits(payload_word_len));
-
-        let (rest, frame) = map(
-            tuple((
-                bits::<_, _, NomError<(&[u8], usize)>, _, _>(payload),
-                Self::parse_masking_key,
-            )),
-            move |(payload, masking_key)| Self {
-                fin,
-                rsv,
-                mask,
-                opcode,
-                length: payload.unwrap_or(payload_word_len),
-                masking_key,
-            },
-        )(rest)?;
 
         Ok((rest.to_owned(), frame))
     }
 }
+
+/// Accumulates a fragmented message (a first text/binary frame with FIN=0
+/// followed by zero or more continuation frames) until the final frame
+/// arrives. Control frames are handled separately by the caller and never
+/// pass through here, since they must not themselves be fragmented.
+#[derive(Debug, Default)]
+pub struct FragmentedMessage {
+    opcode: Option<Opcode>,
+    payload: Vec<u8>,
+}
+
+impl FragmentedMessage {
+    pub fn push(&mut self, opcode: Opcode, payload: &[u8]) -> std::result::Result<(), ProtocolError> {
+        match (opcode, self.opcode) {
+            (Opcode::Continuation, None) => {
+                return Err(ProtocolError::UnexpectedContinuation);
+            }
+            (Opcode::Continuation, Some(_)) => {}
+            (Opcode::Text, None) | (Opcode::Binary, None) => {
+                self.opcode = Some(opcode);
+            }
+            (Opcode::Text, Some(_)) | (Opcode::Binary, Some(_)) => {
+                return Err(ProtocolError::Other(
+                    "new message started before the previous one finished".into(),
+                ));
+            }
+            _ => unreachable!("control frames are not fed into FragmentedMessage"),
+        }
+
+        // `MAX_FRAME_PAYLOAD_LEN` only bounds a single frame; without also
+        // capping the running total, unlimited continuation frames could
+        // still grow `self.payload` without bound.
+        if self.payload.len() as u64 + payload.len() as u64 > MAX_FRAME_PAYLOAD_LEN {
+            return Err(ProtocolError::PayloadTooLarge(
+                self.payload.len() as u64 + payload.len() as u64,
+            ));
+        }
+
+        self.payload.extend_from_slice(payload);
+        Ok(())
+    }
+
+    /// Finalizes the message, validating the accumulated payload as UTF-8
+    /// if it is a text message, and returns it as a single contiguous
+    /// `Bytes`.
+    pub fn finish(self) -> std::result::Result<(Opcode, Bytes), ProtocolError> {
+        let opcode = self
+            .opcode
+            .expect("finish is only called after at least one frame was pushed");
+
+        if opcode == Opcode::Text {
+            std::str::from_utf8(&self.payload).map_err(|_| ProtocolError::InvalidUtf8)?;
+        }
+
+        Ok((opcode, Bytes::from(self.payload)))
+    }
+}